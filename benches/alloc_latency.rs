@@ -0,0 +1,100 @@
+//! Benchmarks that *allocation* latency stays flat as the free list grows,
+//! which is the whole point of bucketing it by size class (see
+//! `crate::free_list`) instead of walking one linear list: allocation jumps
+//! straight to the smallest bucket that can satisfy a request instead of
+//! scanning every free block in address order.
+//!
+//! Only the `alloc` side is timed — `free` also walks `ALLOCATED` (a flat
+//! `Vec` of live blocks, used to tell a real deallocation apart from an
+//! internal split) to verify guards, which is a separate O(live blocks) cost
+//! the free-list bucketing doesn't touch and isn't what this benchmark is
+//! about.
+//!
+//! Exercises `Mac` directly through `GlobalAlloc`, the same way the crate's
+//! own tests do, rather than installing it as `#[global_allocator]` — doing
+//! that here would route every allocation the host process makes (`Vec`
+//! growth included) through `Mac`, which isn't what this benchmark is
+//! measuring.
+//!
+//! No bench harness dependency is available in this tree, so this times
+//! itself with `std::time::Instant` and prints the result rather than
+//! asserting on it — wall-clock thresholds are too machine-dependent to gate
+//! a build on. Run with `cargo bench --bench alloc_latency`, wired up in
+//! `Cargo.toml` via:
+//!
+//! ```toml
+//! [[bench]]
+//! name = "alloc_latency"
+//! harness = false
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::time::Instant;
+
+use mac_check::Mac;
+
+const BATCH: usize = 2_000;
+const SIZES: [usize; 4] = [32, 128, 512, 2048];
+
+/// Allocates `BATCH` blocks (never freeing them mid-measurement), returning
+/// the elapsed time and the allocated pointers for the caller to free.
+/// Sizes cycle through a handful of classes so this exercises more than one
+/// free-list bucket.
+fn timed_alloc_batch() -> (std::time::Duration, Vec<(*mut u8, Layout)>) {
+    let mut allocated = Vec::with_capacity(BATCH);
+    let start = Instant::now();
+    for i in 0..BATCH {
+        let layout = Layout::from_size_align(SIZES[i % SIZES.len()], 8).unwrap();
+        let ptr = unsafe { Mac.alloc(layout) };
+        assert!(!ptr.is_null(), "allocation failed");
+        allocated.push((ptr, layout));
+    }
+    let elapsed = start.elapsed();
+    (elapsed, allocated)
+}
+
+fn free_all(ptrs: Vec<(*mut u8, Layout)>) {
+    for (ptr, layout) in ptrs {
+        unsafe { Mac.dealloc(ptr, layout) };
+    }
+}
+
+fn main() {
+    // Baseline: the arena is still essentially empty (one big free block),
+    // so this is the best case for a single linear free list too.
+    let (baseline, batch) = timed_alloc_batch();
+    free_all(batch);
+
+    // Scatter a large number of blocks across the arena, then free every
+    // other one. Freeing only alternating blocks keeps their still-live
+    // neighbors from coalescing them back into one giant block, so this
+    // leaves the free list holding thousands of separate entries, mostly the
+    // freed 512-byte blocks (requests of up to 256 bytes are served by the
+    // slab layer rather than the free list) — the scenario a single linear
+    // free list degrades badly on.
+    const SCATTERED: usize = 50_000;
+    let mut live = Vec::with_capacity(SCATTERED);
+    for i in 0..SCATTERED {
+        let layout = Layout::from_size_align(SIZES[i % SIZES.len()], 8).unwrap();
+        let ptr = unsafe { Mac.alloc(layout) };
+        assert!(!ptr.is_null(), "allocation failed");
+        live.push((ptr, layout));
+    }
+    let (freed, still_live): (Vec<_>, Vec<_>) =
+        live.into_iter().enumerate().partition(|(i, _)| i % 2 == 0);
+    free_all(freed.into_iter().map(|(_, p)| p).collect());
+
+    let (with_scattered_free_list, batch) = timed_alloc_batch();
+    free_all(batch);
+    free_all(still_live.into_iter().map(|(_, p)| p).collect());
+
+    println!("alloc_latency: {BATCH} allocations, baseline = {baseline:?}");
+    println!(
+        "alloc_latency: {BATCH} allocations with the free list scattered across \
+         {SCATTERED} blocks = {with_scattered_free_list:?}"
+    );
+    println!(
+        "alloc_latency: ratio (scattered / baseline) = {:.2}",
+        with_scattered_free_list.as_secs_f64() / baseline.as_secs_f64().max(f64::EPSILON)
+    );
+}
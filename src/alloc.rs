@@ -1,69 +1,277 @@
 use std::ptr::null_mut;
 
-use crate::{align_up::*, Block, ALLOCATED, FREE_LIST_HEAD};
+use crate::{
+    align_up::*, free_list, init_arena, poison, region, Block, ALLOCATED, ALLOC_LOCK, ARENA_INIT,
+    FREE_LIST_HEADS,
+};
 
 pub unsafe fn alloc(size: usize) -> *mut u8 {
+    ARENA_INIT.call_once(|| init_arena());
+    let _guard = ALLOC_LOCK.lock();
+    alloc_inner(size)
+}
+
+/// The actual allocation logic, without taking [`ALLOC_LOCK`] itself.
+/// `realloc` calls this directly (after taking the lock once for its whole
+/// operation) rather than the public [`alloc`], since the spinlock isn't
+/// reentrant.
+pub(crate) unsafe fn alloc_inner(size: usize) -> *mut u8 {
     if size == 0 {
         return null_mut();
     }
+    // Small, fixed-size requests skip the per-block `Block` header entirely
+    // by going through the slab layer (see `crate::slab`); anything it
+    // doesn't serve (too big, or this call came from the slab layer growing
+    // its own backing chunk) falls through to the free list below.
+    if let Some(ptr) = crate::slab::try_alloc(size) {
+        return ptr;
+    }
     let align = align_of::<Block>();
-    let needed = align_up(size + std::mem::size_of::<Block>(), align);
-
-    let mut prev = *FREE_LIST_HEAD.0.get();
-    let mut current = (*prev).next;
+    let header = std::mem::size_of::<Block>();
+    let needed = align_up(
+        poison::payload_offset(header) + size + poison::back_guard_bytes(),
+        align,
+    );
+    let min_split = header + align;
 
+    // One pass over the buckets per iteration; if nothing fits, grow the
+    // arena with a fresh region sized for this request and try again. Only
+    // gives up once the OS itself won't hand over more memory.
     loop {
-        if (*current).free && (*current).size >= needed {
-            let remainder = (*current).size - needed;
-            let min_split = std::mem::size_of::<Block>() + align;
-            if remainder >= min_split {
-                (*current).free = false;
-                split_block(prev, current, needed);
-            } else {
-                (*current).free = false;
-                (*prev).next = (*current).next;
-                if current == FREE_LIST_HEAD.0.get().read() {
-                    FREE_LIST_HEAD.0.get().write(prev);
+        let start_bucket = free_list::bucket_for_size(needed);
+        for bucket in start_bucket..free_list::N_BUCKETS {
+            let mut current = free_list::head(&FREE_LIST_HEADS, bucket);
+            while !current.is_null() {
+                let next_in_bucket = (*current).next;
+                if (*current).size >= needed {
+                    free_list::remove(&FREE_LIST_HEADS, bucket, current);
+                    (*current).free = false;
+
+                    let remainder = (*current).size - needed;
+                    if remainder >= min_split {
+                        split_block(current, needed);
+                    }
+
+                    ALLOCATED.add(current);
+                    let payload = (current as *mut u8).add(poison::payload_offset(header));
+                    #[cfg(debug_assertions)]
+                    {
+                        println!(
+                            "Allocated!! block: {:?} with size: {}",
+                            current,
+                            (*current).size
+                        );
+                        (*current).requested = size;
+                        poison::init_guards(payload, size);
+                    }
+                    return payload;
                 }
+                current = next_in_bucket;
             }
-            ALLOCATED.add(current);
+        }
+        if !region::grow(needed) {
             #[cfg(debug_assertions)]
-            {
-                println!(
-                    "Allocated!! block: {:?} with size: {}",
-                    current,
-                    (*current).size
-                );
+            println!("No suitable block found for allocation of size: {}", size);
+            return null_mut(); // growing the arena itself failed
+        }
+    }
+}
+
+/// Like [`alloc`], but honors an arbitrary power-of-two `align` rather than
+/// always aligning to `align_of::<Block>()`.
+///
+/// The `Block` header for the returned allocation is placed just before the
+/// aligned payload (ahead of the front guard in debug builds), which is not
+/// necessarily where the candidate free block started. Any leading gap left
+/// behind is split off into its own free block; a gap that is nonzero but
+/// too small to hold a `Block` header is widened by one more `align` first
+/// (see [`fit`]).
+pub unsafe fn alloc_aligned(size: usize, align: usize) -> *mut u8 {
+    ARENA_INIT.call_once(|| init_arena());
+    let _guard = ALLOC_LOCK.lock();
+    alloc_aligned_inner(size, align)
+}
+
+/// The actual aligned-allocation logic, without taking [`ALLOC_LOCK`] itself.
+/// `realloc` calls this directly (after taking the lock once for its whole
+/// operation) rather than the public [`alloc_aligned`], since the spinlock
+/// isn't reentrant — see `alloc_inner`'s doc comment above.
+pub(crate) unsafe fn alloc_aligned_inner(size: usize, align: usize) -> *mut u8 {
+    if size == 0 {
+        return null_mut();
+    }
+    debug_assert!(align.is_power_of_two(), "align must be a power of two");
+    let align = align.max(align_of::<Block>());
+    let header = std::mem::size_of::<Block>();
+    // Distance from a block's start to its payload, leaving room for the
+    // front guard in debug builds (see `poison`).
+    let hdr_off = poison::payload_offset(header);
+    // Threshold for splitting the trailing remainder off a block, matching
+    // the one `alloc`/`split_block` use.
+    let min_split = header + align;
+
+    // A block smaller than `header + size` can never fit regardless of
+    // padding, so we can skip every bucket below that floor.
+    let start_bucket = free_list::bucket_for_size(header + size);
+    // Rounded up to `align_of::<Block>()` so that, if there's enough
+    // remainder to split off a tail block, that tail lands on a valid
+    // `Block` alignment. Independent of any particular candidate block, so
+    // it's computed once up front rather than per iteration.
+    let needed = align_up(
+        hdr_off + size + poison::back_guard_bytes(),
+        align_of::<Block>(),
+    );
+
+    // Same retry-with-growth strategy as `alloc`: one pass over the buckets,
+    // then grow the arena with a fresh region and try again if nothing fit.
+    loop {
+        for bucket in start_bucket..free_list::N_BUCKETS {
+            let mut current = free_list::head(&FREE_LIST_HEADS, bucket);
+            while !current.is_null() {
+                let next_in_bucket = (*current).next;
+
+                if let Some(Fit { aligned, front_pad }) = fit(
+                    current as usize,
+                    (*current).size,
+                    hdr_off,
+                    needed,
+                    align,
+                    header,
+                ) {
+                    free_list::remove(&FREE_LIST_HEADS, bucket, current);
+
+                    let real = (aligned - hdr_off) as *mut Block;
+                    let real_size = (*current).size - front_pad;
+                    let remainder = real_size - needed;
+
+                    if front_pad > 0 {
+                        (*current).size = front_pad;
+                        (*real).prev_phys = current;
+                    } else {
+                        (*real).prev_phys = (*current).prev_phys;
+                    }
+                    (*real).free = false;
+
+                    if remainder >= min_split {
+                        (*real).size = needed;
+                        let tail = (real as *mut u8).add(needed) as *mut Block;
+                        (*tail).size = remainder;
+                        (*tail).prev_phys = real;
+                        (*tail).free = true;
+                        free_list::relink_next_prev_phys(tail);
+                        free_list::push(
+                            &FREE_LIST_HEADS,
+                            free_list::bucket_for_size(remainder),
+                            tail,
+                        );
+                    } else {
+                        (*real).size = real_size;
+                        free_list::relink_next_prev_phys(real);
+                    }
+
+                    if front_pad > 0 {
+                        free_list::push(
+                            &FREE_LIST_HEADS,
+                            free_list::bucket_for_size(front_pad),
+                            current,
+                        );
+                    }
+
+                    ALLOCATED.add(real);
+                    #[cfg(debug_assertions)]
+                    {
+                        println!(
+                            "Allocated!! block: {:?} with size: {} (aligned to {})",
+                            real,
+                            (*real).size,
+                            align
+                        );
+                        (*real).requested = size;
+                        poison::init_guards(aligned as *mut u8, size);
+                    }
+                    return aligned as *mut u8;
+                }
+                current = next_in_bucket;
             }
-            return (current as *mut u8).add(std::mem::size_of::<Block>());
         }
-        if current == FREE_LIST_HEAD.0.get().read() {
-            break;
+        // Worst case, alignment padding can eat up to `align - 16` bytes,
+        // plus one more `align` if `fit` has to widen a too-small front gap,
+        // so ask the region for enough room to cover that.
+        if !region::grow(needed + 2 * align) {
+            #[cfg(debug_assertions)]
+            println!(
+                "No suitable block found for aligned allocation of size: {} align: {}",
+                size, align
+            );
+            return null_mut();
         }
-        prev = current;
-        current = (*current).next;
     }
-    println!("No suitable block found for allocation of size: {}", size);
-    null_mut() // allocation attempts failed block not found
 }
 
-unsafe fn split_block(prev: *mut Block, current: *mut Block, needed: usize) {
+/// Where an aligned allocation would land inside a free block, as computed
+/// by [`fit`].
+struct Fit {
+    /// Aligned payload address the caller gets back.
+    aligned: usize,
+    /// Bytes of the candidate block left before `aligned`'s header, carved
+    /// off into its own free block if nonzero.
+    front_pad: usize,
+}
+
+/// Checks whether an aligned allocation of `needed` total bytes (header +
+/// `size` + any guard overhead, see `alloc_aligned`) fits inside a free
+/// block of `block_size` bytes starting at `block_start`, honoring `align`.
+///
+/// Mirrors the `alloc_start`/`alloc_end` reasoning a linked-list allocator
+/// uses: the payload is pushed up to the next `align` boundary at or after
+/// the block's payload offset (one more `align` if the front gap would be
+/// nonzero but too small for a header), and the candidate is rejected if
+/// what's left of the block after that gap is smaller than `needed`.
+fn fit(
+    block_start: usize,
+    block_size: usize,
+    hdr_off: usize,
+    needed: usize,
+    align: usize,
+    header: usize,
+) -> Option<Fit> {
+    let mut aligned = align_up(block_start + hdr_off, align);
+    let mut front_pad = aligned - hdr_off - block_start;
+
+    // A nonzero front gap can only become its own free `Block` if it's big
+    // enough to hold the header; anything smaller can't be represented as a
+    // standalone free node. Rather than rejecting the candidate outright,
+    // try the next aligned address instead — that pushes the gap forward by
+    // a full `align`, which is always enough to fit the header since
+    // `align >= align_of::<Block>()`.
+    if front_pad != 0 && front_pad < header {
+        aligned += align;
+        front_pad += align;
+    }
+
+    let real_size = block_size.checked_sub(front_pad)?;
+    if needed > real_size {
+        return None;
+    }
+    Some(Fit { aligned, front_pad })
+}
+
+unsafe fn split_block(current: *mut Block, needed: usize) {
     let new_block = (current as *mut u8).add(needed) as *mut Block;
     (*new_block).size = (*current).size - needed;
+    (*new_block).prev_phys = current;
+    // `new_block` lands on memory that previously held another block's
+    // poisoned payload, so `free` must be set explicitly rather than trusted
+    // to be zeroed.
     (*new_block).free = true;
-    (*new_block).next = (*current).next;
-    if prev == current {
-        (*new_block).next = new_block; // if we are splitting the head, point to itself
-    } else {
-        (*prev).next = new_block;
-    }
-
     (*current).size = needed;
 
-    if current == *FREE_LIST_HEAD.0.get() {
-        // if we are freeing the head, update the head
-        FREE_LIST_HEAD.0.get().write(new_block);
-    }
+    free_list::relink_next_prev_phys(new_block);
+    free_list::push(
+        &FREE_LIST_HEADS,
+        free_list::bucket_for_size((*new_block).size),
+        new_block,
+    );
 }
 
 #[cfg(test)]
@@ -71,8 +279,32 @@ mod tests {
     use super::*;
     use crate::{init_arena, ARENA_SIZE};
 
+    #[test]
+    fn test_fit_rejects_block_too_small() {
+        let header = std::mem::size_of::<Block>();
+        assert!(fit(
+            0,
+            header + 16,
+            header,
+            header + 64,
+            align_of::<Block>(),
+            header
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_fit_accepts_exact_fit() {
+        let header = std::mem::size_of::<Block>();
+        let needed = header + 64;
+        let f = fit(0, needed, header, needed, align_of::<Block>(), header);
+        assert!(f.is_some());
+        assert_eq!(f.unwrap().front_pad, 0);
+    }
+
     #[test]
     fn test_alloc_return_aligned_pointer() {
+        let _t = crate::test_lock();
         unsafe {
             init_arena();
             let ptr = alloc(64);
@@ -87,6 +319,7 @@ mod tests {
 
     #[test]
     fn test_alloc_zero_size() {
+        let _t = crate::test_lock();
         unsafe {
             init_arena();
             let ptr = alloc(0);
@@ -96,6 +329,7 @@ mod tests {
 
     #[test]
     fn test_alloc_multiple() {
+        let _t = crate::test_lock();
         unsafe {
             init_arena();
             let ptr1 = alloc(128);
@@ -107,13 +341,46 @@ mod tests {
     }
 
     #[test]
-    fn test_alloc_exceeding_size() {
+    fn test_alloc_exceeding_size_grows_arena() {
+        let _t = crate::test_lock();
         unsafe {
             init_arena();
+            // No longer rejected: exceeding the initial arena's size just
+            // triggers `region::grow` for a fresh backing region.
             let ptr = alloc(ARENA_SIZE + 1);
             assert!(
-                ptr.is_null(),
-                "Allocation exceeding arena size should return null"
+                !ptr.is_null(),
+                "Allocation exceeding the initial arena size should grow instead of failing"
+            );
+            let addr = ptr as usize;
+            assert!(
+                addr % align_of::<Block>() == 0,
+                "Allocated pointer should be aligned"
+            );
+        }
+    }
+
+    #[test]
+    fn test_alloc_aligned_to_64_bytes() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+            let ptr = alloc_aligned(128, 64);
+            assert!(!ptr.is_null(), "Aligned allocation should not return null");
+            assert_eq!(ptr as usize % 64, 0, "Pointer should be 64-byte aligned");
+        }
+    }
+
+    #[test]
+    fn test_alloc_routes_small_sizes_through_slab() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+            let ptr = alloc(32);
+            assert!(!ptr.is_null(), "Allocation failed");
+            assert!(
+                crate::slab::contains(ptr),
+                "a request within the slab's size classes should be served by it"
             );
         }
     }
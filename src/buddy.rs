@@ -0,0 +1,329 @@
+//! An alternative to [`crate::alloc`]'s address-ordered free list: a binary
+//! tree of power-of-two blocks with per-order free lists, trading some
+//! internal fragmentation for O(log n) alloc/free and simpler coalescing.
+//! Opt-in — call [`init_buddy`] and use [`buddy_alloc`]/[`buddy_free`]
+//! directly rather than going through [`crate::Mac`], which still uses the
+//! linked-list allocator.
+//!
+//! **Not thread-safe.** Unlike [`crate::alloc`]/[`crate::free`], nothing in
+//! this module takes a lock — [`buddy_alloc`] and [`buddy_free`] mutate
+//! `BUDDY`'s free lists and `free_size` directly. Calling either from more
+//! than one thread, or concurrently with [`init_buddy`], is a data race.
+
+use std::cell::UnsafeCell;
+use std::ptr::null_mut;
+
+/// Size of the buddy arena; must be a power of two.
+const BUDDY_ARENA_SIZE: usize = 1024 * 1024; // 1 MiB
+/// Smallest block order (2^4 = 16 bytes), large enough to hold a `BuddyHeader`
+/// plus a `FreeNode` link once the block is put back on a free list.
+const MIN_ORDER: usize = 4;
+/// log2(BUDDY_ARENA_SIZE)
+const MAX_ORDER: usize = 20;
+const N_ORDERS: usize = MAX_ORDER - MIN_ORDER + 1;
+
+/// Header recorded at the start of every allocated block so `buddy_free` can
+/// recover the order (and therefore the size) without the caller passing it back.
+#[repr(C, align(8))]
+struct BuddyHeader {
+    order: u8,
+}
+
+/// Free blocks reuse their own memory to store the singly-linked free-list node.
+#[repr(C)]
+struct FreeNode {
+    next: *mut FreeNode,
+}
+
+#[repr(C)]
+struct BuddyArena([u8; BUDDY_ARENA_SIZE]);
+// SAFETY: this only makes `BUDDY` a legal `static`, which `UnsafeCell`
+// otherwise forbids — it does NOT mean concurrent access is sound. See the
+// module-level "Not thread-safe" note: every access still goes through
+// `buddy_alloc`/`buddy_free`, neither of which synchronizes with the other.
+unsafe impl Sync for BuddyArena {}
+
+struct BuddyState {
+    area: UnsafeCell<BuddyArena>,
+    free_lists: UnsafeCell<[*mut FreeNode; N_ORDERS]>,
+    /// Running total of free bytes across every order, for `print_buddy_free_list`.
+    free_size: UnsafeCell<usize>,
+}
+// SAFETY: same caveat as `BuddyArena`'s impl above — required to make `BUDDY`
+// a `static`, not a claim that `BuddyState` tolerates concurrent use.
+unsafe impl Sync for BuddyState {}
+
+static BUDDY: BuddyState = BuddyState {
+    area: UnsafeCell::new(BuddyArena([0; BUDDY_ARENA_SIZE])),
+    free_lists: UnsafeCell::new([null_mut(); N_ORDERS]),
+    free_size: UnsafeCell::new(0),
+};
+
+/// # Safety
+///
+/// Must not be called concurrently with, or while any outstanding
+/// allocation from, [`buddy_alloc`]/[`buddy_free`] — it rebuilds every free
+/// list from scratch, invalidating any block already handed out.
+pub unsafe fn init_buddy() {
+    let lists = &mut *BUDDY.free_lists.get();
+    for head in lists.iter_mut() {
+        *head = null_mut();
+    }
+    *BUDDY.free_size.get() = 0;
+    let base = BUDDY.area.get() as *mut u8;
+    push_free(MAX_ORDER - MIN_ORDER, base);
+}
+
+/// Allocate a block able to hold `size` bytes, rounding up to the smallest
+/// power-of-two order that fits `size` plus the `BuddyHeader`, splitting
+/// larger free blocks in half repeatedly until one of the needed order exists.
+///
+/// # Safety
+///
+/// [`init_buddy`] must have been called first, and not called again while
+/// the returned pointer is still live. Not thread-safe (see the module-level
+/// docs) — callers must not call this, [`buddy_free`], or [`init_buddy`]
+/// concurrently from another thread.
+pub unsafe fn buddy_alloc(size: usize) -> *mut u8 {
+    if size == 0 {
+        return null_mut();
+    }
+    let header_size = std::mem::size_of::<BuddyHeader>();
+    let needed = size + header_size;
+
+    let mut order = MIN_ORDER;
+    while (1usize << order) < needed {
+        order += 1;
+        if order > MAX_ORDER {
+            println!("No suitable buddy block found for allocation of size: {size}");
+            return null_mut();
+        }
+    }
+
+    let mut found = order;
+    while found <= MAX_ORDER && free_list_head(found - MIN_ORDER).is_null() {
+        found += 1;
+    }
+    if found > MAX_ORDER {
+        println!("No suitable buddy block found for allocation of size: {size}");
+        return null_mut();
+    }
+
+    let block = pop_free(found - MIN_ORDER);
+    // Split the block we found down to the order we actually need, handing
+    // the unused half of each split back to its own free list.
+    let mut current_order = found;
+    while current_order > order {
+        current_order -= 1;
+        let half_size = 1usize << current_order;
+        let buddy_block = block.add(half_size);
+        push_free(current_order - MIN_ORDER, buddy_block);
+    }
+
+    (*(block as *mut BuddyHeader)).order = order as u8;
+    block.add(header_size)
+}
+
+/// Free a block returned by `buddy_alloc`, recursively merging with its
+/// buddy (the block of the same size on the other side of the boundary
+/// formed by flipping the order'th bit of its offset) while that buddy is
+/// itself free.
+///
+/// # Safety
+///
+/// `ptr` must be null or have come from [`buddy_alloc`] and not already
+/// have been freed. Not thread-safe (see the module-level docs) — callers
+/// must not call this, [`buddy_alloc`], or [`init_buddy`] concurrently from
+/// another thread.
+pub unsafe fn buddy_free(ptr: *mut u8) {
+    if ptr.is_null() {
+        return;
+    }
+    let header_size = std::mem::size_of::<BuddyHeader>();
+    let mut block = ptr.sub(header_size);
+    let mut order = (*(block as *mut BuddyHeader)).order as usize;
+    let base = BUDDY.area.get() as usize;
+
+    while order < MAX_ORDER {
+        let size = 1usize << order;
+        let addr = block as usize;
+        let buddy_addr = (base + ((addr - base) ^ size)) as *mut u8;
+
+        if !remove_if_free(order - MIN_ORDER, buddy_addr) {
+            break;
+        }
+        block = if buddy_addr < block {
+            buddy_addr
+        } else {
+            block
+        };
+        order += 1;
+    }
+
+    push_free(order - MIN_ORDER, block);
+}
+
+/// Inserts `block` into order `idx`'s free list in address order, so
+/// `pop_free` gives first-fit (lowest address) behavior within an order.
+unsafe fn push_free(idx: usize, block: *mut u8) {
+    let node = block as *mut FreeNode;
+    let lists = &mut *BUDDY.free_lists.get();
+
+    let mut cur = lists[idx];
+    let mut prev: *mut FreeNode = null_mut();
+    while !cur.is_null() && (cur as usize) < (node as usize) {
+        prev = cur;
+        cur = (*cur).next;
+    }
+    (*node).next = cur;
+    if prev.is_null() {
+        lists[idx] = node;
+    } else {
+        (*prev).next = node;
+    }
+
+    *BUDDY.free_size.get() += 1usize << (idx + MIN_ORDER);
+}
+
+unsafe fn pop_free(idx: usize) -> *mut u8 {
+    let lists = &mut *BUDDY.free_lists.get();
+    let head = lists[idx];
+    lists[idx] = (*head).next;
+    *BUDDY.free_size.get() -= 1usize << (idx + MIN_ORDER);
+    head as *mut u8
+}
+
+unsafe fn free_list_head(idx: usize) -> *mut FreeNode {
+    (*BUDDY.free_lists.get())[idx]
+}
+
+unsafe fn remove_if_free(idx: usize, addr: *mut u8) -> bool {
+    let target = addr as *mut FreeNode;
+    let lists = &mut *BUDDY.free_lists.get();
+    let mut cur = lists[idx];
+    let mut prev: *mut FreeNode = null_mut();
+    while !cur.is_null() {
+        if cur == target {
+            if prev.is_null() {
+                lists[idx] = (*cur).next;
+            } else {
+                (*prev).next = (*cur).next;
+            }
+            *BUDDY.free_size.get() -= 1usize << (idx + MIN_ORDER);
+            return true;
+        }
+        prev = cur;
+        cur = (*cur).next;
+    }
+    false
+}
+
+/// 現在のバディ・フリーリストの状態を標準出力に出す（debug用）
+///
+/// # Safety
+///
+/// [`init_buddy`] must have been called first.
+#[cfg(debug_assertions)]
+pub unsafe fn print_buddy_free_list() {
+    println!();
+    println!("---- Buddy Free List ----");
+    for (idx, &head) in (*BUDDY.free_lists.get()).iter().enumerate() {
+        let order = idx + MIN_ORDER;
+        let mut count = 0;
+        let mut cur = head;
+        while !cur.is_null() {
+            count += 1;
+            cur = (*cur).next;
+        }
+        println!(
+            "order {order:<2} (size(B): {:>8}): {count} free",
+            1usize << order
+        );
+    }
+    println!(
+        "Arena Size: {BUDDY_ARENA_SIZE}\nTotal free size: {}",
+        *BUDDY.free_size.get()
+    );
+    println!("--------------------------");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buddy_free_size_tracks_allocations() {
+        // `BUDDY` is shared and not thread-safe; serialize with the other
+        // tests that run in parallel.
+        let _t = crate::test_lock();
+        unsafe {
+            init_buddy();
+            assert_eq!(*BUDDY.free_size.get(), BUDDY_ARENA_SIZE);
+
+            let a = buddy_alloc(16);
+            assert!(!a.is_null());
+            assert!(
+                *BUDDY.free_size.get() < BUDDY_ARENA_SIZE,
+                "allocating should shrink the tracked free size"
+            );
+
+            buddy_free(a);
+            assert_eq!(
+                *BUDDY.free_size.get(),
+                BUDDY_ARENA_SIZE,
+                "freeing the only allocation should coalesce back to the full free size"
+            );
+
+            #[cfg(debug_assertions)]
+            print_buddy_free_list();
+        }
+    }
+
+    #[test]
+    fn test_buddy_alloc_returns_distinct_pointers() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_buddy();
+            let a = buddy_alloc(64);
+            let b = buddy_alloc(64);
+            assert!(!a.is_null() && !b.is_null());
+            assert_ne!(a, b);
+        }
+    }
+
+    #[test]
+    fn test_buddy_splits_down_to_requested_order() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_buddy();
+            // A small request should only consume a small block, leaving the
+            // rest of the arena available for further allocations.
+            let a = buddy_alloc(16);
+            assert!(!a.is_null());
+            let b = buddy_alloc(BUDDY_ARENA_SIZE / 2 - 64);
+            assert!(!b.is_null(), "large sibling block should still be free");
+        }
+    }
+
+    #[test]
+    fn test_buddy_free_coalesces_back_to_whole_arena() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_buddy();
+            let a = buddy_alloc(BUDDY_ARENA_SIZE / 2 - 64);
+            let b = buddy_alloc(BUDDY_ARENA_SIZE / 2 - 64);
+            assert!(!a.is_null() && !b.is_null());
+
+            buddy_free(a);
+            buddy_free(b);
+
+            // Once both buddies are free they should have merged all the way
+            // back up, so a full-arena-sized request succeeds again.
+            let whole = buddy_alloc(BUDDY_ARENA_SIZE - std::mem::size_of::<BuddyHeader>());
+            assert!(
+                !whole.is_null(),
+                "freeing both buddies should coalesce back into one block"
+            );
+        }
+    }
+}
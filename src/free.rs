@@ -1,108 +1,131 @@
-use crate::{Block, ALLOCATED, ARENA_SIZE, FREE_LIST_HEAD};
+use crate::{
+    first_block, free_list, poison, region, Block, ALLOCATED, ALLOC_LOCK, FREE_LIST_HEADS,
+};
 
 pub unsafe fn free(ptr: *mut u8) {
+    let _guard = ALLOC_LOCK.lock();
+    free_inner(ptr);
+}
+
+/// The actual free logic, without taking [`ALLOC_LOCK`] itself. `realloc`
+/// calls this directly (after taking the lock once for its whole operation)
+/// rather than the public [`free`], since the spinlock isn't reentrant.
+pub(crate) unsafe fn free_inner(ptr: *mut u8) {
     if ptr.is_null() {
         return;
     }
-    let mut block = ptr.sub(std::mem::size_of::<Block>()) as *mut Block;
-    (*block).free = true;
-    ALLOCATED.remove(block);
-
-    let headp = FREE_LIST_HEAD.0.get();
-    let head = *headp;
-    if head.is_null() {
-        (*block).next = block;
-        headp.write(block);
+    // Slab-allocated pointers don't have a `Block` header to walk back to;
+    // hand them to the slab layer before assuming one exists below.
+    if crate::slab::try_free(ptr) {
         return;
     }
+    let header_size = std::mem::size_of::<Block>();
+    let mut block = ptr.sub(poison::payload_offset(header_size)) as *mut Block;
+    #[cfg(debug_assertions)]
+    poison::check_not_double_freed(block);
+    (*block).free = true;
+    // A block that was never tracked in `ALLOCATED` didn't come from `alloc`
+    // (e.g. a split-off remainder being pushed straight onto the free list
+    // from `realloc`) and so was never poisoned — nothing to verify.
+    let was_allocated = ALLOCATED.remove(block);
+    #[cfg(debug_assertions)]
+    if was_allocated {
+        poison::verify_and_poison(block, ptr, (*block).requested);
+    }
 
-    let mut current = head;
-    let mut next = (*current).next;
-
+    // Merge forward with the next physical block while it's free.
     loop {
-        // current < block < next
-        if current < block && block < next {
-            break;
-        }
-        if current >= next && (block > current || block < next) {
-            break;
-        }
-        current = next;
-        next = (*current).next;
-        if current == *headp {
+        let next = free_list::next_phys(block);
+        if next.is_null() || !(*next).free {
             break;
         }
+        free_list::remove(
+            &FREE_LIST_HEADS,
+            free_list::bucket_for_size((*next).size),
+            next,
+        );
+        (*block).size += (*next).size;
+        free_list::relink_next_prev_phys(block);
     }
-    // insert
-    (*block).next = next;
-    (*current).next = block;
 
-    // coalescing
-    {
-        let mut next_after = (*block).next;
-        // println!(
-        //     "block: {:?}, added: {:?} next_after: {:?}",
-        //     block,
-        //     (block as *mut u8).add((*block).size),
-        //     next_after
-        // );
-
-        if (block as *mut u8).add((*block).size) == next_after as *mut u8 {
-            // !! Merging with next block
-            (*block).size += (*next).size;
-            (*block).next = (*next).next;
-            if next_after == *headp {
-                headp.write(block);
-            }
-            next_after = (*block).next;
-        }
-
-        if (current as *mut u8).add((*current).size) == block as *mut u8 {
-            // !! Merging with previous block
-            (*current).size += (*block).size;
-            (*current).next = (*block).next;
-            if *headp == block {
-                headp.write(current);
-            }
-            block = current; // blockを更新
+    // Merge backward with the previous physical block while it's free.
+    loop {
+        let prev = (*block).prev_phys;
+        if prev.is_null() || !(*prev).free {
+            break;
         }
+        free_list::remove(
+            &FREE_LIST_HEADS,
+            free_list::bucket_for_size((*prev).size),
+            prev,
+        );
+        (*prev).size += (*block).size;
+        free_list::relink_next_prev_phys(prev);
+        block = prev;
     }
+
+    free_list::push(
+        &FREE_LIST_HEADS,
+        free_list::bucket_for_size((*block).size),
+        block,
+    );
+    // `println!` itself may allocate (e.g. via internal buffering), which
+    // would deadlock against `ALLOC_LOCK` if `Mac` is installed as
+    // `#[global_allocator]` — so this diagnostic is debug-only, never on the
+    // hot path of a release build.
+    #[cfg(debug_assertions)]
     println!("Freed!! {:?}", ptr);
 }
+
 /// 現在のフリーリストの状態を標準出力に出す（debug用）
 pub unsafe fn print_free_list() {
     #[cfg(not(debug_assertions))]
     {
         return; // debugモードでのみ有効
     }
-    let mut current = *FREE_LIST_HEAD.0.get();
     let mut i = 0;
-    let mut sum_free_size = 0;
+    let mut grand_total_size = 0;
+    let mut grand_free_size = 0;
 
     println!();
     println!("---- Free List ----");
-    loop {
+    for (region_idx, (base, end)) in region::all().into_iter().enumerate() {
+        let mut current = if region_idx == 0 {
+            first_block()
+        } else {
+            base as *mut Block
+        };
+        let mut chunk_size = 0;
+        let mut chunk_free_size = 0;
+        loop {
+            println!(
+                "#{:<2}  ptr: {:p}, size(B): {:>8}, free: {}",
+                i,
+                current,
+                (*current).size,
+                (*current).free,
+            );
+            chunk_size += (*current).size;
+            if (*current).free {
+                chunk_free_size += (*current).size;
+            }
+            i += 1;
+            let next = free_list::next_phys(current);
+            if next.is_null() {
+                break; // このリージョンの末尾に到達
+            }
+            current = next;
+        }
         println!(
-            "#{:<2}  ptr: {:p}, size(B): {:>8}, free: {}, next: {:p}",
-            i,
-            current,
-            (*current).size,
-            (*current).free,
-            (*current).next,
+            "  chunk #{region_idx} [{base:#x}, {end:#x}) size(B): {chunk_size:>8}, free(B): {chunk_free_size:>8}, used(B): {}",
+            chunk_size - chunk_free_size
         );
-        sum_free_size += (*current).size;
-        current = (*current).next;
-        i += 1;
-        if current == FREE_LIST_HEAD.0.get().read() || i > 10 {
-            break; // 循環している場合は終了
-        }
-    }
-    if i == 0 {
-        println!("(empty)");
+        grand_total_size += chunk_size;
+        grand_free_size += chunk_free_size;
     }
     println!(
-        "Arena Size: {ARENA_SIZE}\nTotal free size: {sum_free_size}\nUsed Size: {}",
-        ARENA_SIZE - sum_free_size
+        "Arena Size: {grand_total_size}\nTotal free size: {grand_free_size}\nUsed Size: {}",
+        grand_total_size - grand_free_size
     );
     println!("-------------------");
     println!("Allocated blocks:");
@@ -121,6 +144,7 @@ mod tests {
 
     #[test]
     fn test_free() {
+        let _t = crate::test_lock();
         unsafe {
             init_arena();
         }
@@ -131,12 +155,7 @@ mod tests {
                 let p2 = alloc(256);
                 let p3 = alloc(512);
                 let p4 = alloc(1024);
-
-                let before_free = *FREE_LIST_HEAD.0.get();
-                assert!(
-                    !before_free.is_null(),
-                    "Free list should not be empty before freeing"
-                );
+                let _ = p3;
 
                 free(p1);
                 free(p2);
@@ -156,22 +175,52 @@ mod tests {
         }
     }
 
-    fn find_block_in_free_list(ptr: *mut u8) -> bool {
+    #[test]
+    #[cfg_attr(
+        debug_assertions,
+        should_panic(expected = "heap corruption: double free")
+    )]
+    fn test_double_free_is_caught() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+            let p = alloc(64);
+            free(p);
+            free(p);
+        }
+    }
+
+    #[test]
+    fn test_free_does_not_coalesce_across_chunk_boundaries() {
+        let _t = crate::test_lock();
         unsafe {
-            let head = *FREE_LIST_HEAD.0.get();
-            let mut cur = head;
-            let mut found = false;
-            loop {
-                if cur == (ptr as *mut u8).sub(std::mem::size_of::<Block>()) as *mut Block {
-                    found = true;
-                    break;
-                }
-                cur = (*cur).next;
-                if cur == head {
-                    break;
-                }
+            init_arena();
+            // Bigger than the original arena, so `alloc` has to grow a
+            // second chunk to serve it.
+            let p1 = alloc(crate::ARENA_SIZE + 64);
+            assert!(!p1.is_null());
+            assert_eq!(
+                region::all().len(),
+                2,
+                "oversized alloc should grow a second chunk"
+            );
+
+            free(p1);
+            // Each region should still be exactly one free block spanning
+            // its own bounds; merging across chunks would produce a block
+            // bigger than either region.
+            for (base, end) in region::all() {
+                let block = base as *mut Block;
+                assert!((*block).free);
+                assert_eq!((*block).size, end - base);
             }
-            found
+        }
+    }
+
+    fn find_block_in_free_list(ptr: *mut u8) -> bool {
+        unsafe {
+            let block = ptr.sub(poison::payload_offset(std::mem::size_of::<Block>())) as *mut Block;
+            free_list::contains(&FREE_LIST_HEADS, block)
         }
     }
 }
@@ -0,0 +1,99 @@
+use std::cell::UnsafeCell;
+use std::ptr::null_mut;
+
+use crate::{region, Block};
+
+/// Number of size-class buckets the free list is segregated into.
+pub const N_BUCKETS: usize = 8;
+/// Upper size bound (inclusive, of a block's whole size, `Block` header
+/// included) for every bucket but the last, which catches anything bigger
+/// than `THRESHOLDS`'s max.
+const THRESHOLDS: [usize; N_BUCKETS - 1] = [32, 64, 128, 256, 512, 1024, 2048];
+
+/// Picks the smallest bucket whose blocks could possibly satisfy `size`.
+pub fn bucket_for_size(size: usize) -> usize {
+    THRESHOLDS
+        .iter()
+        .position(|&t| size <= t)
+        .unwrap_or(N_BUCKETS - 1)
+}
+
+/// One free-list head per size class. `Block::next` links blocks within the
+/// same bucket; physical (address-order) neighbors are tracked separately via
+/// `Block::prev_phys` so coalescing doesn't depend on bucket order.
+pub struct FreeListHeads(pub UnsafeCell<[*mut Block; N_BUCKETS]>);
+unsafe impl Sync for FreeListHeads {}
+
+pub unsafe fn head(heads: &FreeListHeads, bucket: usize) -> *mut Block {
+    (*heads.0.get())[bucket]
+}
+
+/// Clears every bucket. Used by `init_arena` so re-initializing the arena
+/// (as the tests do) doesn't leave stale blocks from a previous run linked
+/// into the free list.
+pub unsafe fn reset(heads: &FreeListHeads) {
+    let table = heads.0.get();
+    for bucket in (*table).iter_mut() {
+        *bucket = null_mut();
+    }
+}
+
+pub unsafe fn push(heads: &FreeListHeads, bucket: usize, block: *mut Block) {
+    let table = heads.0.get();
+    (*block).free = true;
+    (*block).next = (*table)[bucket];
+    (*table)[bucket] = block;
+}
+
+pub unsafe fn remove(heads: &FreeListHeads, bucket: usize, block: *mut Block) {
+    let table = heads.0.get();
+    let mut cur = (*table)[bucket];
+    let mut prev: *mut Block = null_mut();
+    while !cur.is_null() {
+        if cur == block {
+            if prev.is_null() {
+                (*table)[bucket] = (*cur).next;
+            } else {
+                (*prev).next = (*cur).next;
+            }
+            return;
+        }
+        prev = cur;
+        cur = (*cur).next;
+    }
+}
+
+#[cfg(test)]
+pub unsafe fn contains(heads: &FreeListHeads, block: *mut Block) -> bool {
+    for bucket in 0..N_BUCKETS {
+        let mut cur = head(heads, bucket);
+        while !cur.is_null() {
+            if cur == block {
+                return true;
+            }
+            cur = (*cur).next;
+        }
+    }
+    false
+}
+
+/// The physical block immediately after `block`, or null if `block` ends at
+/// its region's boundary. Never crosses into a different region, even if
+/// one happens to sit right after another in the address space.
+pub unsafe fn next_phys(block: *mut Block) -> *mut Block {
+    let block_addr = block as usize;
+    let end = block_addr + (*block).size;
+    match region::end_containing(block_addr) {
+        Some(region_end) if end == region_end => null_mut(),
+        _ => end as *mut Block,
+    }
+}
+
+/// After `block`'s size has been finalized, make sure the physical block
+/// that now immediately follows it (if any) points `prev_phys` back at it.
+pub unsafe fn relink_next_prev_phys(block: *mut Block) {
+    let next = next_phys(block);
+    if !next.is_null() {
+        (*next).prev_phys = block;
+    }
+}
@@ -0,0 +1,162 @@
+use std::alloc::{GlobalAlloc, Layout};
+
+use crate::align_up::align_up;
+use crate::Block;
+
+/// Zero-sized handle that lets MAC be dropped in as `#[global_allocator]`.
+///
+/// Forwards to the existing arena free list (see [`crate::alloc`] / [`crate::free`]),
+/// which lazily runs [`crate::init_arena`] on first use so callers don't need to set
+/// the arena up themselves before the runtime starts allocating.
+pub struct Mac;
+
+unsafe impl GlobalAlloc for Mac {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.align() > align_of::<Block>() {
+            return crate::alloc::alloc_aligned(layout.size(), layout.align());
+        }
+        crate::alloc::alloc(layout.size())
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc(layout);
+        if !ptr.is_null() {
+            std::ptr::write_bytes(ptr, 0, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Held across the sanity check below too, since it reads the slab's
+        // chunk lists and the block header; hence `free_inner` at the end.
+        let _guard = crate::ALLOC_LOCK.lock();
+        // Slab-allocated pointers (see `crate::slab`) don't have a `Block`
+        // header at all, so there's nothing to sanity-check against here.
+        if layout.align() <= align_of::<Block>() && !crate::slab::contains(ptr) {
+            let header_size = std::mem::size_of::<Block>();
+            let block = ptr.sub(crate::poison::payload_offset(header_size)) as *mut Block;
+            // Over-aligned allocations went through `alloc_aligned`, whose
+            // block sizing also accounts for front-padding, so this formula
+            // (matching `alloc`'s) only holds for the natural-alignment path.
+            let needed = align_up(
+                crate::poison::payload_offset(header_size)
+                    + layout.size()
+                    + crate::poison::back_guard_bytes(),
+                align_of::<Block>(),
+            );
+            // `alloc_inner` only splits a found block down to `needed` when
+            // the leftover remainder is `>= min_split` (see `alloc.rs`);
+            // otherwise the block legitimately keeps its original, larger
+            // `size`. So the block can be anywhere in `[needed, needed +
+            // min_split)`, not just exactly `needed`.
+            let min_split = header_size + align_of::<Block>();
+            debug_assert!(
+                (*block).size >= needed && (*block).size < needed + min_split,
+                "dealloc layout {:?} does not match the block recorded at alloc time \
+                 (block size {}, expected in [{}, {}))",
+                layout,
+                (*block).size,
+                needed,
+                needed + min_split
+            );
+        }
+        crate::free::free_inner(ptr);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if layout.align() > align_of::<Block>() {
+            return crate::realloc::realloc_aligned(ptr, new_size, layout.align());
+        }
+        crate::realloc::realloc(ptr, new_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_arena;
+
+    #[test]
+    fn test_global_alloc_round_trip() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+            let layout = Layout::from_size_align(128, align_of::<Block>()).unwrap();
+            let ptr = Mac.alloc(layout);
+            assert!(!ptr.is_null(), "GlobalAlloc::alloc should not return null");
+            Mac.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn test_global_alloc_honors_over_alignment() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+            let layout = Layout::from_size_align(128, 64).unwrap();
+            let ptr = Mac.alloc(layout);
+            assert!(
+                !ptr.is_null(),
+                "over-aligned GlobalAlloc::alloc should not return null"
+            );
+            assert_eq!(
+                ptr as usize % 64,
+                0,
+                "returned pointer should honor the requested alignment"
+            );
+            Mac.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn test_dealloc_does_not_panic_on_unsplit_remainder() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+            let layout_a = Layout::from_size_align(300, align_of::<Block>()).unwrap();
+            let a = Mac.alloc(layout_a);
+            assert!(!a.is_null());
+            // Guard allocation so `a`'s block can't coalesce forward once
+            // freed below.
+            let guard = Mac.alloc(layout_a);
+            assert!(!guard.is_null());
+
+            Mac.dealloc(a, layout_a);
+
+            // Reuses `a`'s freed block without splitting it: the leftover
+            // remainder after carving out 270 bytes is smaller than
+            // `min_split`, so the block legitimately keeps its larger,
+            // original size. `dealloc` must not mistake that for corruption.
+            let layout_b = Layout::from_size_align(270, align_of::<Block>()).unwrap();
+            let b = Mac.alloc(layout_b);
+            assert!(!b.is_null());
+            Mac.dealloc(b, layout_b);
+
+            Mac.dealloc(guard, layout_a);
+        }
+    }
+
+    #[test]
+    fn test_realloc_preserves_over_alignment_on_move() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+            let layout = Layout::from_size_align(128, 64).unwrap();
+            let p = Mac.alloc(layout);
+            assert!(!p.is_null());
+            // Keep the block right after `p` allocated so there's nothing
+            // free to extend into, forcing `realloc` onto the move path.
+            let _guard = Mac.alloc(Layout::from_size_align(128, align_of::<Block>()).unwrap());
+
+            let new_p = Mac.realloc(p, layout, 4096);
+            assert!(!new_p.is_null(), "Reallocation failed");
+            assert_eq!(
+                new_p as usize % 64,
+                0,
+                "moving to grow should still honor the original alignment"
+            );
+
+            Mac.dealloc(new_p, Layout::from_size_align(4096, 64).unwrap());
+        }
+    }
+}
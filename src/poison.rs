@@ -0,0 +1,114 @@
+#[cfg(debug_assertions)]
+use crate::Block;
+
+/// Marks both sides of a payload; a guard that doesn't read back as this
+/// means something wrote out of bounds of its allocation.
+#[cfg(debug_assertions)]
+pub const GUARD_PATTERN: u32 = 0xDEADBEEF;
+/// Number of guard words placed on each side of a payload. Chosen so
+/// `GUARD_BYTES` is itself a multiple of `align_of::<Block>()`, keeping the
+/// header-to-payload offset (and therefore block alignment) unaffected.
+#[cfg(debug_assertions)]
+pub const GUARD_WORDS: usize = 4;
+/// Size in bytes of one guard region (front or back).
+#[cfg(debug_assertions)]
+pub const GUARD_BYTES: usize = GUARD_WORDS * std::mem::size_of::<u32>();
+
+/// Written across a payload at `alloc` time so reading uninitialized memory
+/// comes back as an obviously-bogus pattern instead of stale zeros.
+#[cfg(debug_assertions)]
+pub const ALLOC_POISON: u32 = 0xCAFEBABE;
+/// Written across a payload at `free` time, distinct from `ALLOC_POISON` so a
+/// dangling read after free is equally obvious and tells the two states apart.
+#[cfg(debug_assertions)]
+pub const FREE_POISON: u32 = 0xFEEDFACE;
+
+/// Distance from the start of a `Block` to the payload it hands out. In debug
+/// builds this leaves room for the front guard right after the header; in
+/// release builds (where none of this module is wired in) it's just `header`.
+#[cfg(debug_assertions)]
+pub fn payload_offset(header: usize) -> usize {
+    header + GUARD_BYTES
+}
+#[cfg(not(debug_assertions))]
+pub fn payload_offset(header: usize) -> usize {
+    header
+}
+
+/// Extra bytes a block must reserve after the payload for the back guard.
+#[cfg(debug_assertions)]
+pub fn back_guard_bytes() -> usize {
+    GUARD_BYTES
+}
+#[cfg(not(debug_assertions))]
+pub fn back_guard_bytes() -> usize {
+    0
+}
+
+#[cfg(debug_assertions)]
+unsafe fn fill_pattern(ptr: *mut u8, len: usize, pattern: u32) {
+    let bytes = pattern.to_ne_bytes();
+    for i in 0..len {
+        ptr.add(i).write(bytes[i % 4]);
+    }
+}
+
+/// Write the front and back guards around a freshly-handed-out payload and
+/// poison the payload itself. Called once, right before `alloc`/`alloc_aligned`
+/// return the pointer to the caller.
+#[cfg(debug_assertions)]
+pub unsafe fn init_guards(payload: *mut u8, len: usize) {
+    fill_pattern(payload.sub(GUARD_BYTES), GUARD_BYTES, GUARD_PATTERN);
+    fill_pattern(payload, len, ALLOC_POISON);
+    fill_pattern(payload.add(len), GUARD_BYTES, GUARD_PATTERN);
+}
+
+/// Verify both guards around `payload` are intact, panicking with `block` if
+/// either was clobbered, then poison the payload so a dangling read after
+/// this `free` stands out just as clearly.
+#[cfg(debug_assertions)]
+pub unsafe fn verify_and_poison(block: *mut Block, payload: *mut u8, len: usize) {
+    check_guard(block, payload.sub(GUARD_BYTES), "front");
+    check_guard(block, payload.add(len), "back");
+    fill_pattern(payload, len, FREE_POISON);
+}
+
+/// Panics if `block` is already marked free, catching a double free before
+/// it corrupts the free list (the same block would otherwise get pushed onto
+/// it twice).
+#[cfg(debug_assertions)]
+pub unsafe fn check_not_double_freed(block: *mut Block) {
+    if (*block).free {
+        panic!("heap corruption: double free of block {:?}", block);
+    }
+}
+
+#[cfg(debug_assertions)]
+unsafe fn check_guard(block: *mut Block, guard: *mut u8, which: &str) {
+    for i in 0..GUARD_WORDS {
+        let word = (guard.add(i * 4) as *const u32).read_unaligned();
+        if word != GUARD_PATTERN {
+            panic!(
+                "heap corruption: {which} guard clobbered for block {:?} (found {:#x})",
+                block, word
+            );
+        }
+    }
+}
+
+/// Poison the newly-extended tail of a payload that grew in place (realloc's
+/// grow path) and move its back guard out to the new length. The front guard
+/// and the original `old_len` bytes of payload are left untouched.
+#[cfg(debug_assertions)]
+pub unsafe fn extend_guards(payload: *mut u8, old_len: usize, new_len: usize) {
+    fill_pattern(payload.add(old_len), new_len - old_len, ALLOC_POISON);
+    fill_pattern(payload.add(new_len), GUARD_BYTES, GUARD_PATTERN);
+}
+
+/// Move a payload's back guard to reflect a new, smaller length (realloc's
+/// shrink-in-place path). The front guard and surviving payload bytes are
+/// left untouched.
+#[cfg(debug_assertions)]
+pub unsafe fn shrink_guard(payload: *mut u8, new_len: usize) {
+    fill_pattern(payload.add(new_len), GUARD_BYTES, GUARD_PATTERN);
+}
@@ -1,8 +1,8 @@
 use std::ptr::{self, null_mut};
 
 use crate::{
-    align_up::align_up, alloc::alloc, free::free, Block, ALLOCATED, ARENA, ARENA_SIZE,
-    FREE_LIST_HEAD,
+    align_up::align_up, alloc::alloc_inner, free::free_inner, free_list, init_arena, poison, Block,
+    ALLOC_LOCK, ARENA_INIT, FREE_LIST_HEADS,
 };
 
 // Minimum size to split a block
@@ -17,21 +17,66 @@ const MIN_SPLIT: usize = std::mem::size_of::<Block>() + 16;
 -	不可能なら alloc して memcpy、古いブロックを free（OOM の場合は元ブロックを残す）
 */
 pub unsafe fn realloc(ptr: *mut u8, new_size: usize) -> *mut u8 {
+    realloc_aligned(ptr, new_size, align_of::<Block>())
+}
+
+/// Like [`realloc`], but honors an arbitrary power-of-two `align` rather
+/// than always treating the block as naturally aligned — mirrors the
+/// `alloc`/`alloc_aligned` split in [`crate::alloc`]. `Mac::realloc` calls
+/// this directly with the original allocation's `Layout::align()`.
+pub unsafe fn realloc_aligned(ptr: *mut u8, new_size: usize, align: usize) -> *mut u8 {
+    ARENA_INIT.call_once(|| init_arena());
+    // Held for the whole operation; internally this calls `alloc_inner` and
+    // `free_inner` rather than the public, self-locking `alloc`/`free`, since
+    // `ALLOC_LOCK` isn't reentrant.
+    let _guard = ALLOC_LOCK.lock();
     if ptr.is_null() {
-        return unsafe { alloc(new_size) };
+        return unsafe { alloc_for(new_size, align) };
     }
     if new_size == 0 {
         unsafe {
-            free(ptr);
+            free_inner(ptr);
         }
         return null_mut();
     }
+    // The payload address never moves for an in-place shrink or grow below,
+    // so if it already honored `align` it still does; only the move path
+    // (which hands back a fresh address) needs to request `align` itself.
+    debug_assert_eq!(
+        ptr as usize % align,
+        0,
+        "realloc called with an alignment the original allocation doesn't satisfy"
+    );
+    // Slab-allocated pointers (see `crate::slab`) have no `Block` header to
+    // read below; handle them separately, either in place (the slot's class
+    // size already covers `new_size`) or by moving to a fresh allocation.
+    // Over-aligned requests go through `alloc_aligned`, which never uses the
+    // slab, so a slab pointer only arrives here with the natural alignment.
+    if let Some(class_size) = unsafe { crate::slab::class_size_of(ptr) } {
+        if new_size <= class_size {
+            return ptr;
+        }
+        let new_ptr = unsafe { alloc_for(new_size, align) };
+        if new_ptr.is_null() {
+            return null_mut();
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(ptr, new_ptr, core::cmp::min(class_size, new_size));
+            free_inner(ptr);
+        }
+        return new_ptr;
+    }
     let header_size = std::mem::size_of::<Block>();
-    let align = align_of::<Block>();
-    let needed = align_up(new_size + header_size, align);
+    let needed = align_up(
+        poison::payload_offset(header_size) + new_size + poison::back_guard_bytes(),
+        align_of::<Block>(),
+    );
 
-    let block = unsafe { (ptr as *mut u8).sub(header_size) as *mut Block };
+    let block = unsafe { ptr.sub(poison::payload_offset(header_size)) as *mut Block };
     let old_size = (*block).size;
+    #[cfg(debug_assertions)]
+    let old_data = (*block).requested;
+    #[cfg(not(debug_assertions))]
     let old_data = old_size - header_size;
 
     if old_size >= needed {
@@ -40,23 +85,39 @@ pub unsafe fn realloc(ptr: *mut u8, new_size: usize) -> *mut u8 {
             unsafe {
                 let new_block = (block as *mut u8).add(needed) as *mut Block;
                 (*new_block).size = remainder;
-                (*new_block).free = true;
-                (*new_block).next = null_mut();
+                (*new_block).prev_phys = block;
+                // Lands on reused memory, so `free` can't be trusted to come
+                // back zeroed; `free_inner` below is what actually marks it
+                // free (and would mistake a stale `true` here for a double
+                // free).
+                (*new_block).free = false;
                 (*block).size = needed;
+                free_list::relink_next_prev_phys(new_block);
 
-                let data = (new_block as *mut u8).add(header_size);
-                free(data);
+                let data = (new_block as *mut u8).add(poison::payload_offset(header_size));
+                free_inner(data);
             }
         }
 
+        #[cfg(debug_assertions)]
+        {
+            poison::shrink_guard(ptr, new_size);
+            (*block).requested = new_size;
+        }
+
         return ptr;
     }
 
     if try_in_place_extend_next_free_block(block, needed) {
+        #[cfg(debug_assertions)]
+        {
+            poison::extend_guards(ptr, old_data, new_size);
+            (*block).requested = new_size;
+        }
         return ptr;
     }
 
-    let new_ptr = alloc(new_size);
+    let new_ptr = unsafe { alloc_for(new_size, align) };
     if new_ptr.is_null() {
         // Allocation failed, return null
         return null_mut();
@@ -65,51 +126,63 @@ pub unsafe fn realloc(ptr: *mut u8, new_size: usize) -> *mut u8 {
     unsafe {
         ptr::copy_nonoverlapping(ptr, new_ptr, core::cmp::min(old_data, new_size));
 
-        let old_block_ptr = block;
-        ALLOCATED.remove(old_block_ptr);
-        free(ptr);
+        // `free_inner` does its own `ALLOCATED.remove`, which is what drives
+        // guard verification (see `poison::verify_and_poison`) — removing it
+        // here first would make that lookup return `false` and silently skip
+        // verification on this move path.
+        free_inner(ptr);
     }
 
     new_ptr
 }
 
+/// Allocates a fresh block for `realloc`'s move path, honoring `align` when
+/// it's wider than what the natural (`Block`-only) path already guarantees —
+/// mirrors the `alloc`/`alloc_aligned` split in [`crate::alloc`].
+unsafe fn alloc_for(size: usize, align: usize) -> *mut u8 {
+    if align <= align_of::<Block>() {
+        alloc_inner(size)
+    } else {
+        crate::alloc::alloc_aligned_inner(size, align)
+    }
+}
+
 // Try to extend 'block' by merging with the immediate next physical block if it's free.
 // Return true if after operation block.size >= needed.
 unsafe fn try_in_place_extend_next_free_block(block: *mut Block, needed: usize) -> bool {
-    let base = ARENA.area.get() as usize;
-    let arena_end = base + ARENA_SIZE;
-
-    let block_end = (block as *mut u8).add((*block).size) as *mut Block;
-    let block_end_addr = block_end as usize;
-
-    if block_end_addr < base || block_end_addr >= arena_end {
+    let next = free_list::next_phys(block);
+    if next.is_null() {
         return false; // Block end is out of bounds
     }
-    if block_end == block {
-        return false; // Block is self-referential, cannot extend
-    }
-
-    let next = block_end;
     if !(*next).free {
         return false; // Next block is not free
     }
 
     // can combine
     // remove next from free list
-    remove_free_block(next);
+    free_list::remove(
+        &FREE_LIST_HEADS,
+        free_list::bucket_for_size((*next).size),
+        next,
+    );
 
     (*block).size = (*block).size + (*next).size;
+    free_list::relink_next_prev_phys(block);
     if (*block).size >= needed {
         let remainder = (*block).size - needed;
         if remainder >= MIN_SPLIT {
             let new_block = (block as *mut u8).add(needed) as *mut Block;
             (*new_block).size = remainder;
-            (*new_block).free = true;
-            (*new_block).next = null_mut();
+            (*new_block).prev_phys = block;
+            // See the matching comment in `realloc`'s shrink path above:
+            // `free_inner` is what marks this block free.
+            (*new_block).free = false;
             (*block).size = needed;
+            free_list::relink_next_prev_phys(new_block);
 
-            let data = (new_block as *mut u8).add(std::mem::size_of::<Block>());
-            free(data);
+            let data =
+                (new_block as *mut u8).add(poison::payload_offset(std::mem::size_of::<Block>()));
+            free_inner(data);
         }
         return true;
     }
@@ -117,48 +190,24 @@ unsafe fn try_in_place_extend_next_free_block(block: *mut Block, needed: usize)
     false
 }
 
-unsafe fn remove_free_block(block: *mut Block) {
-    let headp = FREE_LIST_HEAD.0.get();
-    let head = *headp;
-
-    if head.is_null() {
-        return; // No free blocks
-    }
-
-    if head == block && (*head).next == head {
-        headp.write(null_mut()); // Only one block, now removed
-        return;
-    }
-
-    let mut current = head;
-    loop {
-        if (*current).next == block {
-            (*current).next = (*block).next;
-            if block == head {
-                headp.write(current); // Update head if we removed the head block
-            }
-            return;
-        }
-        current = (*current).next;
-        if current == head {
-            break;
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::alloc::alloc;
+    use crate::free::free;
     use crate::init_arena;
 
     #[test]
     fn test_realloc() {
+        let _t = crate::test_lock();
         unsafe {
             init_arena();
-            let ptr = alloc(64);
+            // Bigger than the slab's largest class, so this exercises the
+            // linked-list allocator's in-place grow rather than the slab.
+            let ptr = alloc(400);
             assert!(!ptr.is_null(), "Allocation failed");
 
-            let new_ptr = realloc(ptr, 128);
+            let new_ptr = realloc(ptr, 800);
             assert!(!new_ptr.is_null(), "Reallocation failed");
             assert_eq!(new_ptr, ptr, "Reallocation did not return the same pointer");
 
@@ -168,6 +217,7 @@ mod tests {
 
     #[test]
     fn test_realloc_zero_size() {
+        let _t = crate::test_lock();
         unsafe {
             init_arena();
             let ptr = alloc(64);
@@ -182,6 +232,7 @@ mod tests {
     }
     #[test]
     fn grow_shrink_realloc() {
+        let _t = crate::test_lock();
         unsafe {
             init_arena();
             let ptr = alloc(128);
@@ -197,4 +248,33 @@ mod tests {
             free(p3);
         }
     }
+
+    #[test]
+    #[cfg_attr(
+        debug_assertions,
+        should_panic(expected = "heap corruption: back guard clobbered")
+    )]
+    fn test_realloc_move_path_checks_guards() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+            // Bigger than the slab's largest class, so this goes through
+            // the linked-list allocator (and its guards) rather than the
+            // slab, which doesn't reserve guard space.
+            let p = alloc(300);
+            assert!(!p.is_null(), "Allocation failed");
+            // Keep the block right after `p` allocated so there's nothing
+            // free to extend into, forcing `realloc` onto the move path.
+            let _q = alloc(300);
+
+            // Same corruption the reviewer's repro clobbers: a write past
+            // the end of the payload into the back guard.
+            *p.add(300) = 0xff;
+
+            // The move path must still run the same guard verification a
+            // direct `free` would, instead of silently copying out the
+            // corrupted block.
+            realloc(p, 600);
+        }
+    }
 }
@@ -0,0 +1,81 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::UnsafeCell;
+use std::ptr::null_mut;
+
+use crate::{free_list, Block, ARENA_SIZE, FREE_LIST_HEADS};
+
+/// Size of a freshly-grown region when nothing bigger is asked for, matching
+/// the original static arena's size.
+const MIN_CHUNK_SIZE: usize = ARENA_SIZE;
+
+/// `[base, end)` byte ranges for every backing region the allocator currently
+/// owns — the original static arena plus any chunks grown on demand via
+/// [`grow`]. `free_list::next_phys` consults this so physical-neighbor
+/// coalescing never walks from one region into another; a block sitting at
+/// the end of its region simply has no next.
+struct Regions(UnsafeCell<Vec<(usize, usize)>>);
+unsafe impl Sync for Regions {}
+
+static REGIONS: Regions = Regions(UnsafeCell::new(Vec::new()));
+
+/// Drop every tracked region and start over with just `[base, end)`. Called
+/// by `init_arena` so re-initializing (as the tests do) doesn't leave stale
+/// ranges from a previous run around.
+pub unsafe fn reset(base: usize, end: usize) {
+    let regions = &mut *REGIONS.0.get();
+    regions.clear();
+    regions.push((base, end));
+}
+
+/// The end of the region containing `addr`, or `None` if `addr` isn't inside
+/// any tracked region.
+pub unsafe fn end_containing(addr: usize) -> Option<usize> {
+    (*REGIONS.0.get())
+        .iter()
+        .find(|&&(base, end)| addr >= base && addr < end)
+        .map(|&(_, end)| end)
+}
+
+/// Ask the real system allocator — [`System`], not whatever's currently
+/// installed as `#[global_allocator]` (which may well be [`crate::Mac`]
+/// itself) — for a new backing region able to hold at least `min_size`
+/// bytes, seed it as one large free `Block`, and link that block into the
+/// free list. Returns `false` if the underlying OS allocation failed.
+///
+/// Only a `System`-backed (mmap/brk, depending on the platform's allocator)
+/// growth strategy is implemented; a `no_std`/embedded build would need a
+/// different region source plugged in here.
+pub unsafe fn grow(min_size: usize) -> bool {
+    let chunk_size = min_size.max(MIN_CHUNK_SIZE);
+    let layout = match Layout::from_size_align(chunk_size, align_of::<Block>()) {
+        Ok(layout) => layout,
+        Err(_) => return false,
+    };
+    let base = System.alloc(layout);
+    if base.is_null() {
+        return false;
+    }
+
+    let regions = &mut *REGIONS.0.get();
+    regions.push((base as usize, base as usize + chunk_size));
+
+    let block = base as *mut Block;
+    (*block).size = chunk_size;
+    (*block).prev_phys = null_mut();
+    // Freshly `System`-allocated memory, so `free` must be set explicitly
+    // rather than trusted to come back zeroed.
+    (*block).free = true;
+    free_list::push(
+        &FREE_LIST_HEADS,
+        free_list::bucket_for_size(chunk_size),
+        block,
+    );
+    true
+}
+
+/// Every tracked region's `[base, end)` range, in growth order (the original
+/// static arena first). Used by `print_free_list` to walk the whole arena,
+/// not just the region it started in.
+pub unsafe fn all() -> Vec<(usize, usize)> {
+    (*REGIONS.0.get()).clone()
+}
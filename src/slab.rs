@@ -0,0 +1,239 @@
+//! A bitmap-backed slab layer for small, fixed-size-class allocations,
+//! removing the per-object [`crate::Block`] header the general-purpose
+//! allocator in [`crate::alloc`] would otherwise pay. `crate::alloc`'s own
+//! `alloc_inner` already tries this first for sizes within the largest
+//! class; [`alloc`]/[`free`] are exposed directly too, for callers that want
+//! to use the slab explicitly rather than relying on that routing.
+
+use std::cell::UnsafeCell;
+use std::ptr::null_mut;
+
+use crate::ALLOC_LOCK;
+
+/// Fixed size classes served by the slab layer; anything bigger falls
+/// through to the general-purpose linked-list allocator in [`crate::alloc`].
+const SIZE_CLASSES: [usize; 5] = [16, 32, 64, 128, 256];
+const N_CLASSES: usize = SIZE_CLASSES.len();
+/// Objects per chunk — one bit per object in a `u64` bitmap.
+const OBJECTS_PER_CHUNK: usize = u64::BITS as usize;
+
+/// One chunk backing a size class: `OBJECTS_PER_CHUNK` fixed-size slots
+/// carved out of a single underlying allocation, with a bit per slot
+/// tracking occupancy (`1` = in use).
+struct SlabChunk {
+    base: usize,
+    bitmap: u64,
+}
+
+struct SlabClass(UnsafeCell<Vec<SlabChunk>>);
+unsafe impl Sync for SlabClass {}
+
+static CLASSES: [SlabClass; N_CLASSES] = [
+    SlabClass(UnsafeCell::new(Vec::new())),
+    SlabClass(UnsafeCell::new(Vec::new())),
+    SlabClass(UnsafeCell::new(Vec::new())),
+    SlabClass(UnsafeCell::new(Vec::new())),
+    SlabClass(UnsafeCell::new(Vec::new())),
+];
+
+/// Smallest size class able to hold `size` bytes, or `None` if `size`
+/// exceeds the biggest class.
+fn class_for_size(size: usize) -> Option<usize> {
+    SIZE_CLASSES
+        .iter()
+        .position(|&class_size| size <= class_size)
+}
+
+/// Drops every tracked chunk. `init_arena` calls this so re-initializing the
+/// arena (as the tests do) doesn't leave slab chunks pointing at addresses
+/// the fresh arena has since reused.
+pub(crate) unsafe fn reset() {
+    for class in CLASSES.iter() {
+        (*class.0.get()).clear();
+    }
+}
+
+/// Which class and chunk index `addr` falls within, if any currently
+/// tracked chunk covers it. Shared by every lookup below so the "is this a
+/// slab pointer" scan only lives in one place.
+unsafe fn locate(addr: usize) -> Option<(usize, usize)> {
+    for (class_idx, class) in CLASSES.iter().enumerate() {
+        let class_size = SIZE_CLASSES[class_idx];
+        let chunk_bytes = class_size * OBJECTS_PER_CHUNK;
+        let chunks = &*class.0.get();
+        if let Some(pos) = chunks
+            .iter()
+            .position(|c| addr >= c.base && addr < c.base + chunk_bytes)
+        {
+            return Some((class_idx, pos));
+        }
+    }
+    None
+}
+
+/// Whether `ptr` was handed out by the slab layer (as opposed to having
+/// fallen through to [`crate::alloc`]). Lets callers that don't otherwise
+/// know which allocator served a pointer — [`crate::mac`]'s `dealloc`
+/// sanity check, in particular — special-case it.
+pub(crate) unsafe fn contains(ptr: *mut u8) -> bool {
+    !ptr.is_null() && locate(ptr as usize).is_some()
+}
+
+/// The size class backing `ptr`, or `None` if it isn't a slab pointer.
+/// [`crate::realloc`] uses this in place of reading a `Block` header, which
+/// slab allocations don't have.
+pub(crate) unsafe fn class_size_of(ptr: *mut u8) -> Option<usize> {
+    let (class_idx, _) = locate(ptr as usize)?;
+    Some(SIZE_CLASSES[class_idx])
+}
+
+/// # Safety
+///
+/// [`crate::init_arena`] (or the first use of `crate::alloc`/`crate::Mac`,
+/// which lazily calls it) must have run first, since a full chunk falls
+/// through to allocating backing memory from the arena.
+pub unsafe fn alloc(size: usize) -> *mut u8 {
+    let _guard = ALLOC_LOCK.lock();
+    alloc_inner(size)
+}
+
+/// The actual slab allocation logic, without taking [`ALLOC_LOCK`] itself.
+pub(crate) unsafe fn alloc_inner(size: usize) -> *mut u8 {
+    match try_alloc(size) {
+        Some(ptr) => ptr,
+        None => crate::alloc::alloc_inner(size),
+    }
+}
+
+/// Tries to serve `size` out of the slab layer. Returns `None` if `size` is
+/// zero or exceeds the biggest class (the caller should fall back to
+/// [`crate::alloc`] itself), `Some(ptr)` otherwise — `ptr` is only null if
+/// growing the backing arena for a fresh chunk failed.
+pub(crate) unsafe fn try_alloc(size: usize) -> Option<*mut u8> {
+    if size == 0 {
+        return None;
+    }
+    let class_idx = class_for_size(size)?;
+    let class_size = SIZE_CLASSES[class_idx];
+    let chunks = &mut *CLASSES[class_idx].0.get();
+
+    for chunk in chunks.iter_mut() {
+        // `!bitmap`'s trailing zero count is the index of the first clear
+        // bit in `bitmap` — a fast way to find a free slot without scanning
+        // bit by bit.
+        let idx = (!chunk.bitmap).trailing_zeros() as usize;
+        if idx < OBJECTS_PER_CHUNK {
+            chunk.bitmap |= 1u64 << idx;
+            return Some((chunk.base + idx * class_size) as *mut u8);
+        }
+    }
+
+    // Every existing chunk for this class is full; carve a fresh one out of
+    // the general-purpose arena. `class_size * OBJECTS_PER_CHUNK` always
+    // exceeds the biggest class itself, so this can't recurse back into the
+    // slab layer.
+    let base = crate::alloc::alloc_inner(class_size * OBJECTS_PER_CHUNK);
+    if base.is_null() {
+        return Some(null_mut());
+    }
+    chunks.push(SlabChunk {
+        base: base as usize,
+        bitmap: 1,
+    });
+    Some(base)
+}
+
+/// # Safety
+///
+/// `ptr` must be null or have come from [`alloc`] and not already have been
+/// freed.
+pub unsafe fn free(ptr: *mut u8) {
+    let _guard = ALLOC_LOCK.lock();
+    free_inner(ptr);
+}
+
+/// The actual slab free logic, without taking [`ALLOC_LOCK`] itself.
+pub(crate) unsafe fn free_inner(ptr: *mut u8) {
+    if !try_free(ptr) {
+        // Not a slab pointer — it must have fallen through to the general
+        // allocator at alloc time (size exceeded the biggest class).
+        crate::free::free_inner(ptr);
+    }
+}
+
+/// Tries to free `ptr` as a slab pointer. Returns `false` (and does nothing)
+/// if `ptr` isn't currently tracked by any slab chunk, so the caller can
+/// fall back to treating it as a `Block`-backed allocation.
+pub(crate) unsafe fn try_free(ptr: *mut u8) -> bool {
+    if ptr.is_null() {
+        return false;
+    }
+    let addr = ptr as usize;
+    let Some((class_idx, pos)) = locate(addr) else {
+        return false;
+    };
+
+    let class_size = SIZE_CLASSES[class_idx];
+    let chunks = &mut *CLASSES[class_idx].0.get();
+    let chunk = &mut chunks[pos];
+    let idx = (addr - chunk.base) / class_size;
+    chunk.bitmap &= !(1u64 << idx);
+
+    // Hand the whole chunk back to the arena once nothing in it is still in
+    // use.
+    if chunk.bitmap == 0 {
+        let base = chunk.base;
+        chunks.remove(pos);
+        crate::free::free_inner(base as *mut u8);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_arena;
+
+    #[test]
+    fn test_slab_alloc_returns_distinct_pointers() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+            let a = alloc(16);
+            let b = alloc(16);
+            let c = alloc(16);
+            assert!(!a.is_null() && !b.is_null() && !c.is_null());
+            assert_ne!(a, b);
+            assert_ne!(b, c);
+        }
+    }
+
+    #[test]
+    fn test_slab_reuses_freed_slot() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+            let a = alloc(32);
+            let _b = alloc(32);
+            assert!(!a.is_null());
+
+            free(a);
+            let c = alloc(32);
+            assert_eq!(c, a, "freeing a slot should let the next alloc reuse it");
+        }
+    }
+
+    #[test]
+    fn test_slab_falls_through_for_oversized_allocations() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+            let ptr = alloc(1024);
+            assert!(
+                !ptr.is_null(),
+                "sizes bigger than the largest class should fall through to alloc"
+            );
+            free(ptr);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Guards the global allocator state (`ARENA`, `FREE_LIST_HEADS`, `ALLOCATED`,
+/// `REGIONS`) so concurrent `alloc`/`free`/`realloc` calls from different
+/// threads don't race on it.
+///
+/// A spinlock rather than [`std::sync::Mutex`] because the critical sections
+/// here are short and non-blocking, and because `Mac` may itself be installed
+/// as `#[global_allocator]` — a blocking `Mutex` can pull in allocation paths
+/// of its own, where a plain atomic flag does not.
+pub struct SpinLock {
+    locked: AtomicBool,
+}
+
+impl SpinLock {
+    pub const fn new() -> Self {
+        SpinLock {
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    /// Spins until the lock is free, then holds it until the returned guard
+    /// is dropped. Not reentrant — locking it twice on the same thread
+    /// deadlocks, so callers that need to call into another locked entry
+    /// point internally (e.g. `realloc` calling `alloc`) must go through the
+    /// `*_inner` functions instead.
+    pub fn lock(&self) -> SpinLockGuard<'_> {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                std::hint::spin_loop();
+            }
+        }
+        SpinLockGuard { lock: self }
+    }
+}
+
+unsafe impl Sync for SpinLock {}
+
+pub struct SpinLockGuard<'a> {
+    lock: &'a SpinLock,
+}
+
+impl Drop for SpinLockGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::alloc::alloc;
+    use crate::free::free;
+    use crate::free_list;
+    use crate::init_arena;
+    use crate::realloc::realloc;
+    use crate::region;
+    use crate::Block;
+
+    /// Hammers `alloc`/`realloc`/`free` from several threads at once, then
+    /// checks the free list comes back well-formed: once every allocation
+    /// has been freed, each region should have fully coalesced back into a
+    /// single free block spanning it.
+    #[test]
+    fn stress_concurrent_alloc_free_realloc() {
+        let _t = crate::test_lock();
+        unsafe {
+            init_arena();
+        }
+
+        // Raw pointers aren't `Send`, so pointers are threaded through as
+        // `usize` and only reinterpreted as `*mut u8` on the allocator side
+        // of the lock.
+        let handles: Vec<_> = (0..8u64)
+            .map(|t| {
+                thread::spawn(move || unsafe {
+                    let mut ptrs: Vec<usize> = Vec::new();
+                    for i in 0..200u64 {
+                        let size = 16 + (t * 37 + i * 13) % 200;
+                        let p = alloc(size as usize);
+                        if p.is_null() {
+                            continue;
+                        }
+                        ptrs.push(p as usize);
+
+                        if i % 3 == 0 {
+                            let new_size = 16 + (i * 7) % 300;
+                            let grown = realloc(p, new_size as usize);
+                            if !grown.is_null() {
+                                *ptrs.last_mut().unwrap() = grown as usize;
+                            }
+                        }
+                        if i % 2 == 0 {
+                            if let Some(p) = ptrs.pop() {
+                                free(p as *mut u8);
+                            }
+                        }
+                    }
+                    ptrs
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let ptrs = handle.join().expect("allocator thread panicked");
+            for p in ptrs {
+                unsafe {
+                    free(p as *mut u8);
+                }
+            }
+        }
+
+        unsafe {
+            for (base, end) in region::all() {
+                let block = base as *mut Block;
+                assert!((*block).free, "region at {:p} should be fully free", block);
+                assert_eq!(
+                    (*block).size,
+                    end - base,
+                    "region at {:p} should have coalesced back into one block",
+                    block
+                );
+                assert!(
+                    free_list::next_phys(block).is_null(),
+                    "region at {:p} should have no further physical blocks",
+                    block
+                );
+            }
+        }
+    }
+}